@@ -1,206 +1,1013 @@
 use futures::executor::block_on;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server};
 use rand::prelude::*;
 use rusoto_core::{Region, RusotoError};
-use rusoto_s3::{GetObjectRequest, ListObjectsV2Request, PutObjectRequest, S3Client, S3};
-use std::{env, sync::Arc, time::Instant};
+use rusoto_credential::{AwsCredentials, DefaultCredentialsProvider, ProvideAwsCredentials};
+use rusoto_s3::util::{PreSignedRequest, PreSignedRequestOption};
+use rusoto_s3::{
+    AbortMultipartUploadRequest, CompleteMultipartUploadRequest, CompletedMultipartUpload,
+    CompletedPart, CreateMultipartUploadRequest, GetObjectRequest, ListObjectsV2Request,
+    PutObjectRequest, S3Client, UploadPartRequest, S3,
+};
+use serde::Deserialize;
+use std::{
+    cmp::min,
+    collections::HashMap,
+    convert::Infallible,
+    env,
+    future::Future,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicI64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
 use tokio::io::AsyncReadExt;
 use tokio::time::sleep;
 
+// Upper bounds (in ms) for the Prometheus latency histograms: the default buckets most
+// Prometheus client libraries ship with, converted to ms, with a 1ms bucket in place of 10s.
+const LATENCY_BUCKETS_MS: &[f64] = &[
+    1.0, 5.0, 10.0, 25.0, 50.0, 100.0, 250.0, 500.0, 1000.0, 2500.0, 5000.0,
+];
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Op {
+    Put,
+    Get,
+    List,
+    Multipart,
+}
+
+// An object size, either a single fixed value (`size = 4096`) or a uniform range
+// (`size = { min = 1024, max = 1048576 }`).
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(untagged)]
+enum SizeSpec {
+    Fixed(usize),
+    Range { min: usize, max: usize },
+}
+
+impl SizeSpec {
+    fn sample(&self) -> usize {
+        match *self {
+            SizeSpec::Fixed(size) => size,
+            SizeSpec::Range { min, max } if min < max => thread_rng().gen_range(min..=max),
+            SizeSpec::Range { min, .. } => min,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct WorkloadConfig {
+    name: String,
+    op: Op,
+    thread_num: usize,
+    ops_per_thread: usize,
+    key_prefix: String,
+    #[serde(default)]
+    size: Option<SizeSpec>,
+    #[serde(default)]
+    part_size: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Config {
+    endpoint: String,
+    bucket: String,
+    #[serde(default)]
+    max_retries: usize,
+    #[serde(default)]
+    verify: bool,
+    #[serde(default)]
+    presign: bool,
+    // When set, serve a live Prometheus exposition-format snapshot of `stats_vec` on
+    // `0.0.0.0:<metrics_port>/metrics` for the duration of the run.
+    #[serde(default)]
+    metrics_port: Option<u16>,
+    workloads: Vec<WorkloadConfig>,
+}
+
 #[derive(Debug)]
 enum RequestType {
     Put,
+    PutPart,
     Get,
+    List,
+    PresignPut,
+    PresignGet,
 }
 
 #[derive(Debug)]
 struct Stats {
+    workload: String,
     start_time: Instant,
     end_time: Instant,
     request_type: RequestType,
     file_size: usize,
+    retries: usize,
+    // Time spent generating the presigned URL, tracked separately from the transfer itself.
+    // `None` for non-presigned request types.
+    sign_time_ms: Option<u128>,
 }
 
-#[tokio::main]
-async fn main() {
-    let num_args = env::args().len();
-    if num_args != 8 {
-        println!(
-            "Usage: {} endpoint bucket root_prefix put_thread_num put_per_thread get_thread_num get_per_thread",
-            env::args().nth(0).unwrap()
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(100);
+const RETRY_MAX_DELAY: Duration = Duration::from_secs(10);
+
+// Window size for the rolling throughput gauge in the live Prometheus snapshot: only requests
+// that finished within this long of "now" count toward it, so the gauge tracks current
+// throughput rather than the whole-run average.
+const ROLLING_THROUGHPUT_WINDOW: Duration = Duration::from_secs(5);
+
+// Populated on PUT when `verify` is set, keyed by object key, so GET can check the bytes it
+// reads back against what was actually written.
+type DigestMap = Arc<Mutex<HashMap<String, (md5::Digest, usize)>>>;
+
+#[derive(Debug, Default)]
+struct VerifyStats {
+    corruption_count: usize,
+    length_mismatch_count: usize,
+}
+
+// Clients and shared state handed to every spawned workload thread.
+#[derive(Clone)]
+struct Shared {
+    s3: S3Client,
+    http_client: reqwest::Client,
+    region: Region,
+    credentials: AwsCredentials,
+    bucket: String,
+    max_retries: usize,
+    verify: bool,
+    presign: bool,
+    stats_vec: Arc<Mutex<Vec<Stats>>>,
+    digests: DigestMap,
+    verify_stats: Arc<Mutex<VerifyStats>>,
+    in_flight: Arc<AtomicI64>,
+    // Run-wide counter used to give every written object a distinct key, even when several
+    // threads in the same workload sample the same file size.
+    next_key_id: Arc<AtomicUsize>,
+}
+
+// Increments `counter` for as long as the guard is alive, so an operation's in-flight window is
+// just its lifetime on the stack.
+struct InFlightGuard<'a>(&'a AtomicI64);
+
+impl<'a> InFlightGuard<'a> {
+    fn start(counter: &'a AtomicI64) -> Self {
+        counter.fetch_add(1, Ordering::Relaxed);
+        InFlightGuard(counter)
+    }
+}
+
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+fn is_retryable<E>(err: &RusotoError<E>) -> bool {
+    match err {
+        RusotoError::HttpDispatch(_) => true,
+        RusotoError::Unknown(resp) => resp.status.is_server_error(),
+        _ => false,
+    }
+}
+
+// Retries `make_request` up to `max_retries` times on `HttpDispatch` and 5xx errors, with
+// full-jitter exponential backoff (a uniform random sleep in `0..=backoff`). Returns the final
+// result along with how many retries were actually performed.
+async fn retry_with_backoff<T, E, F, Fut>(
+    max_retries: usize,
+    mut make_request: F,
+) -> (Result<T, RusotoError<E>>, usize)
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, RusotoError<E>>>,
+{
+    let mut attempt = 0;
+    loop {
+        match make_request().await {
+            Ok(v) => return (Ok(v), attempt),
+            Err(e) => {
+                if attempt >= max_retries || !is_retryable(&e) {
+                    return (Err(e), attempt);
+                }
+                let backoff = min(
+                    RETRY_BASE_DELAY * 2u32.pow(attempt.min(31) as u32),
+                    RETRY_MAX_DELAY,
+                );
+                let jitter =
+                    Duration::from_millis(thread_rng().gen_range(0..=backoff.as_millis() as u64));
+                sleep(jitter).await;
+                attempt += 1;
+            }
+        }
+    }
+}
+
+// Like `retry_with_backoff`, but for the raw `reqwest` calls a presigned-URL request makes
+// instead of going through rusoto. A request is retried on transport-level errors (connect
+// failures, timeouts) and 5xx responses, using the same full-jitter exponential backoff.
+async fn retry_http_with_backoff<F, Fut>(
+    max_retries: usize,
+    mut make_request: F,
+) -> (reqwest::Result<reqwest::Response>, usize)
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = reqwest::Result<reqwest::Response>>,
+{
+    let mut attempt = 0;
+    loop {
+        let retryable = match make_request().await {
+            Ok(resp) if !resp.status().is_server_error() => return (Ok(resp), attempt),
+            Ok(resp) => Ok(resp),
+            Err(e) if e.is_connect() || e.is_timeout() || e.is_request() => Err(e),
+            Err(e) => return (Err(e), attempt),
+        };
+        if attempt >= max_retries {
+            return (retryable, attempt);
+        }
+        let backoff = min(
+            RETRY_BASE_DELAY * 2u32.pow(attempt.min(31) as u32),
+            RETRY_MAX_DELAY,
         );
-        std::process::exit(1);
+        let jitter = Duration::from_millis(thread_rng().gen_range(0..=backoff.as_millis() as u64));
+        sleep(jitter).await;
+        attempt += 1;
     }
+}
 
-    let endpoint = env::args().nth(1).unwrap();
-    let bucket = env::args().nth(2).unwrap();
-    let root_prefix = env::args().nth(3).unwrap();
-    let put_thread_num = env::args().nth(4).unwrap().parse::<usize>().unwrap();
-    let put_per_thread = env::args().nth(5).unwrap().parse::<usize>().unwrap();
-    let get_thread_num = env::args().nth(6).unwrap().parse::<usize>().unwrap();
-    let get_per_thread = env::args().nth(7).unwrap().parse::<usize>().unwrap();
+// Uploads `body` to `key` using the multipart API, splitting it into `part_size`-sized chunks.
+// Pushes one `Stats` entry per part plus one whole-upload entry into `shared.stats_vec`.
+// Aborts the upload on any error so the bucket doesn't accumulate dangling uploads.
+async fn put_multipart(shared: &Shared, workload: &str, key: &str, body: &[u8], part_size: usize) {
+    let s3 = &shared.s3;
+    let bucket = &shared.bucket;
+    let whole_start_time = Instant::now();
 
-    let s3 = S3Client::new(Region::Custom {
-        name: "us-east-2".to_owned(),
-        endpoint: endpoint,
-    });
+    let create_req = CreateMultipartUploadRequest {
+        bucket: bucket.to_owned(),
+        key: key.to_owned(),
+        ..Default::default()
+    };
+    let (create_result, create_retries) = retry_with_backoff(shared.max_retries, || {
+        s3.create_multipart_upload(create_req.clone())
+    })
+    .await;
+    let upload_id = match create_result {
+        Ok(resp) => match resp.upload_id {
+            Some(upload_id) => upload_id,
+            None => {
+                eprintln!("CreateMultipartUpload response had no upload_id");
+                return;
+            }
+        },
+        Err(e) => {
+            eprintln!("Error creating multipart upload: {:?}", e);
+            return;
+        }
+    };
 
-    let stats_vec = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let mut completed_parts = Vec::new();
+    for (i, chunk) in body.chunks(part_size).enumerate() {
+        let part_number = (i + 1) as i64;
+        let part_start_time = Instant::now();
+        let (part_result, part_retries) = retry_with_backoff(shared.max_retries, || {
+            s3.upload_part(UploadPartRequest {
+                bucket: bucket.to_owned(),
+                key: key.to_owned(),
+                upload_id: upload_id.clone(),
+                part_number,
+                body: Some(chunk.to_vec().into()),
+                ..Default::default()
+            })
+        })
+        .await;
+        match part_result {
+            Ok(resp) => {
+                let part_end_time = Instant::now();
+                shared.stats_vec.lock().unwrap().push(Stats {
+                    workload: workload.to_owned(),
+                    start_time: part_start_time,
+                    end_time: part_end_time,
+                    request_type: RequestType::PutPart,
+                    file_size: chunk.len(),
+                    retries: part_retries,
+                    sign_time_ms: None,
+                });
+                match resp.e_tag {
+                    Some(e_tag) => completed_parts.push(CompletedPart {
+                        e_tag: Some(e_tag),
+                        part_number: Some(part_number),
+                    }),
+                    None => {
+                        eprintln!("UploadPart response for part {} had no ETag", part_number);
+                        abort_multipart(s3, bucket, key, &upload_id).await;
+                        return;
+                    }
+                }
+            }
+            Err(e) => {
+                eprintln!("Error uploading part {}: {:?}", part_number, e);
+                abort_multipart(s3, bucket, key, &upload_id).await;
+                return;
+            }
+        }
+    }
 
-    let mut tasks_future = Vec::new();
+    let complete_req = CompleteMultipartUploadRequest {
+        bucket: bucket.to_owned(),
+        key: key.to_owned(),
+        upload_id: upload_id.clone(),
+        multipart_upload: Some(CompletedMultipartUpload {
+            parts: Some(completed_parts),
+        }),
+        ..Default::default()
+    };
+    let (complete_result, complete_retries) = retry_with_backoff(shared.max_retries, || {
+        s3.complete_multipart_upload(complete_req.clone())
+    })
+    .await;
+    match complete_result {
+        Ok(_) => {
+            let whole_end_time = Instant::now();
+            // Per-part retries are already recorded on each `PutPart` entry above, so the
+            // whole-upload entry only carries the create/complete retries — summing every
+            // entry's `retries` for the run-wide total must not double-count a part's retries.
+            shared.stats_vec.lock().unwrap().push(Stats {
+                workload: workload.to_owned(),
+                start_time: whole_start_time,
+                end_time: whole_end_time,
+                request_type: RequestType::Put,
+                file_size: body.len(),
+                retries: create_retries + complete_retries,
+                sign_time_ms: None,
+            });
+        }
+        Err(e) => {
+            eprintln!("Error completing multipart upload: {:?}", e);
+            abort_multipart(s3, bucket, key, &upload_id).await;
+        }
+    }
+}
+
+// Percentile over an already-sorted slice of millisecond latencies: the element at index
+// `round((len - 1) * pct)`.
+fn percentile(sorted: &[u128], pct: f64) -> u128 {
+    if sorted.is_empty() {
+        return 0;
+    }
+    let idx = (((sorted.len() - 1) as f64) * pct).round() as usize;
+    sorted[idx]
+}
+
+// Prints count/size/latency-percentile/throughput stats for one request type. Throughput is
+// total bytes divided by wall-clock time (earliest start to latest end), not the sum of
+// per-request durations, since summing double-counts concurrent work.
+fn print_latency_summary(label: &str, entries: &[&Stats]) {
+    if entries.is_empty() {
+        return;
+    }
+
+    let mut durations: Vec<u128> = entries
+        .iter()
+        .map(|s| s.end_time.duration_since(s.start_time).as_millis())
+        .collect();
+    durations.sort_unstable();
+
+    let count = durations.len();
+    let total_size: usize = entries.iter().map(|s| s.file_size).sum();
+    let mean = durations.iter().sum::<u128>() / count as u128;
+    let min = durations[0];
+    let max = durations[count - 1];
+    let p50 = percentile(&durations, 0.50);
+    let p90 = percentile(&durations, 0.90);
+    let p99 = percentile(&durations, 0.99);
+    let p999 = percentile(&durations, 0.999);
+
+    let earliest_start = entries.iter().map(|s| s.start_time).min().unwrap();
+    let latest_end = entries.iter().map(|s| s.end_time).max().unwrap();
+    let wall_clock_secs = latest_end.duration_since(earliest_start).as_secs_f64();
+    let throughput_mb_s = if wall_clock_secs > 0.0 {
+        (total_size as f64 / 1024.0 / 1024.0) / wall_clock_secs
+    } else {
+        0.0
+    };
+
+    println!(
+        "  {} stats: count={}, total_size={} MB, throughput={:.2} MB/s, mean={}ms, min={}ms, p50={}ms, p90={}ms, p99={}ms, p999={}ms, max={}ms",
+        label,
+        count,
+        total_size / 1024 / 1024,
+        throughput_mb_s,
+        mean,
+        min,
+        p50,
+        p90,
+        p99,
+        p999,
+        max
+    );
+}
+
+// Prints the mean URL-signing time for a presigned workload, kept separate from the transfer
+// latency reported by `print_latency_summary` so signing overhead doesn't hide in the numbers.
+fn print_presign_sign_time(entries: &[&Stats]) {
+    let sign_times: Vec<u128> = entries.iter().filter_map(|s| s.sign_time_ms).collect();
+    if sign_times.is_empty() {
+        return;
+    }
+    let mean = sign_times.iter().sum::<u128>() / sign_times.len() as u128;
+    println!("    sign_time: mean={}ms", mean);
+}
+
+fn presigned_put_url(
+    region: &Region,
+    credentials: &AwsCredentials,
+    bucket: &str,
+    key: &str,
+) -> String {
+    let req = PutObjectRequest {
+        bucket: bucket.to_owned(),
+        key: key.to_owned(),
+        ..Default::default()
+    };
+    req.get_presigned_url(region, credentials, &PreSignedRequestOption::default())
+}
+
+fn presigned_get_url(
+    region: &Region,
+    credentials: &AwsCredentials,
+    bucket: &str,
+    key: &str,
+) -> String {
+    let req = GetObjectRequest {
+        bucket: bucket.to_owned(),
+        key: key.to_owned(),
+        ..Default::default()
+    };
+    req.get_presigned_url(region, credentials, &PreSignedRequestOption::default())
+}
+
+async fn abort_multipart(s3: &S3Client, bucket: &str, key: &str, upload_id: &str) {
+    let abort_req = AbortMultipartUploadRequest {
+        bucket: bucket.to_owned(),
+        key: key.to_owned(),
+        upload_id: upload_id.to_owned(),
+        ..Default::default()
+    };
+    if let Err(e) = s3.abort_multipart_upload(abort_req).await {
+        eprintln!("Error aborting multipart upload: {:?}", e);
+    }
+}
+
+// Lists every object under `key_prefix`, retrying each page on transient errors.
+async fn list_all(shared: &Shared, key_prefix: &str) -> Vec<rusoto_s3::Object> {
+    let mut request = ListObjectsV2Request {
+        bucket: shared.bucket.clone(),
+        prefix: Some(key_prefix.to_owned()),
+        ..Default::default()
+    };
+    let mut objects = Vec::new();
+    loop {
+        let (result, _retries) = retry_with_backoff(shared.max_retries, || {
+            shared.s3.list_objects_v2(request.clone())
+        })
+        .await;
+        let result = result.unwrap();
+        objects.extend(result.contents.unwrap_or_default());
+        if result.next_continuation_token.is_none() {
+            break;
+        }
+        request.continuation_token = result.next_continuation_token;
+    }
+    objects
+}
+
+async fn run_put_workload(shared: Shared, wl: WorkloadConfig) {
+    let size = wl.size.expect("put workload requires `size`");
+    for _ in 0..wl.ops_per_thread {
+        let _in_flight = InFlightGuard::start(&shared.in_flight);
+        let file_size = size.sample();
+        let key_id = shared.next_key_id.fetch_add(1, Ordering::Relaxed);
+        let file_name = format!("put_{}_{}", file_size, key_id);
+        let key = format!("{}/{}", wl.key_prefix, file_name);
+        let body: Vec<u8> = (0..file_size).map(|_| thread_rng().gen()).collect();
+        // Computed once and reused for both the verify map and `content_md5` below, rather than
+        // hashing the whole body twice.
+        let digest = shared.verify.then(|| md5::compute(&body));
+
+        if let Some(digest) = digest {
+            shared
+                .digests
+                .lock()
+                .unwrap()
+                .insert(key.clone(), (digest, body.len()));
+        }
+
+        if shared.presign {
+            let sign_start = Instant::now();
+            let url = presigned_put_url(&shared.region, &shared.credentials, &shared.bucket, &key);
+            let sign_time_ms = sign_start.elapsed().as_millis();
+
+            let start_time = Instant::now();
+            let (result, retries) = retry_http_with_backoff(shared.max_retries, || {
+                shared.http_client.put(&url).body(body.clone()).send()
+            })
+            .await;
+            match result {
+                Ok(resp) if resp.status().is_success() => {
+                    let end_time = Instant::now();
+                    shared.stats_vec.lock().unwrap().push(Stats {
+                        workload: wl.name.clone(),
+                        start_time,
+                        end_time,
+                        request_type: RequestType::PresignPut,
+                        file_size,
+                        retries,
+                        sign_time_ms: Some(sign_time_ms),
+                    });
+                }
+                Ok(resp) => {
+                    eprintln!("Error presign-putting object: status {}", resp.status());
+                }
+                Err(e) => {
+                    eprintln!("Error presign-putting object: {:?}", e);
+                }
+            }
+            continue;
+        }
+
+        let start_time = Instant::now();
+        let (result, retries) = retry_with_backoff(shared.max_retries, || {
+            shared.s3.put_object(PutObjectRequest {
+                bucket: shared.bucket.clone(),
+                key: key.clone(),
+                body: Some(body.clone().into()),
+                content_md5: digest.map(|d| base64::encode(d.0)),
+                ..Default::default()
+            })
+        })
+        .await;
+        match result {
+            Ok(_) => {
+                let end_time = Instant::now();
+                shared.stats_vec.lock().unwrap().push(Stats {
+                    workload: wl.name.clone(),
+                    start_time,
+                    end_time,
+                    request_type: RequestType::Put,
+                    file_size,
+                    retries,
+                    sign_time_ms: None,
+                });
+            }
+            Err(RusotoError::HttpDispatch(_)) => {}
+            Err(e) => {
+                eprintln!("Error putting object: {:?}", e);
+            }
+        }
+    }
+}
+
+async fn run_multipart_workload(shared: Shared, wl: WorkloadConfig) {
+    let size = wl.size.expect("multipart workload requires `size`");
+    let part_size = wl
+        .part_size
+        .expect("multipart workload requires `part_size`");
+    for _ in 0..wl.ops_per_thread {
+        let _in_flight = InFlightGuard::start(&shared.in_flight);
+        let file_size = size.sample();
+        let key_id = shared.next_key_id.fetch_add(1, Ordering::Relaxed);
+        let file_name = format!("put_{}_{}", file_size, key_id);
+        let key = format!("{}/{}", wl.key_prefix, file_name);
+        let body: Vec<u8> = (0..file_size).map(|_| thread_rng().gen()).collect();
+
+        if shared.verify {
+            let digest = md5::compute(&body);
+            shared
+                .digests
+                .lock()
+                .unwrap()
+                .insert(key.clone(), (digest, body.len()));
+        }
+
+        put_multipart(&shared, &wl.name, &key, &body, part_size).await;
+    }
+}
+
+async fn run_get_workload(shared: Shared, wl: WorkloadConfig) {
+    for _ in 0..wl.ops_per_thread {
+        let _in_flight = InFlightGuard::start(&shared.in_flight);
+        let objects = list_all(&shared, &wl.key_prefix).await;
+        if objects.is_empty() {
+            sleep(Duration::from_secs(1)).await;
+            continue;
+        }
+        let key = objects[thread_rng().gen_range(0..objects.len())]
+            .key
+            .clone()
+            .unwrap();
+
+        if shared.presign {
+            let sign_start = Instant::now();
+            let url = presigned_get_url(&shared.region, &shared.credentials, &shared.bucket, &key);
+            let sign_time_ms = sign_start.elapsed().as_millis();
 
-    // spawn put threads
-    for _ in 0..put_thread_num {
-        let s3 = s3.clone();
-        let stats_vec = Arc::clone(&stats_vec);
-        let bucket = bucket.clone();
-        let root_prefix = root_prefix.clone();
-        let put_task_future = tokio::task::spawn(async move {
-            for _ in 0..put_per_thread {
-                let file_size = thread_rng().gen_range(1024..1024 * 1024 * 100);
-                let file_name = format!("put_{}", file_size);
-                let key = format!("{}/{}", root_prefix, file_name);
-                let start_time = Instant::now();
-                let body: Vec<u8> = (0..file_size).map(|_| thread_rng().gen()).collect();
-                let put_req = PutObjectRequest {
-                    bucket: bucket.clone(),
-                    key: key.clone(),
-                    body: Some(body.into()),
-                    ..Default::default()
-                };
-                println!("before put");
-                match s3.put_object(put_req).await {
-                    Ok(_) => {
+            let start_time = Instant::now();
+            let (result, retries) = retry_http_with_backoff(shared.max_retries, || {
+                shared.http_client.get(&url).send()
+            })
+            .await;
+            match result {
+                Ok(resp) if resp.status().is_success() => match resp.bytes().await {
+                    Ok(buf) => {
                         let end_time = Instant::now();
-                        let stats = Stats {
+                        check_integrity(&shared, &key, &buf);
+                        shared.stats_vec.lock().unwrap().push(Stats {
+                            workload: wl.name.clone(),
                             start_time,
                             end_time,
-                            request_type: RequestType::Put,
-                            file_size,
-                        };
-                        stats_vec.lock().unwrap().push(stats);
-                    }
-                    Err(RusotoError::HttpDispatch(_)) => {
-                        println!("HttpDispatch");
+                            request_type: RequestType::PresignGet,
+                            file_size: buf.len(),
+                            retries,
+                            sign_time_ms: Some(sign_time_ms),
+                        });
                     }
                     Err(e) => {
-                        eprintln!("Error putting object: {:?}", e);
+                        eprintln!("Error reading presign-get body: {:?}", e);
                     }
+                },
+                Ok(resp) => {
+                    eprintln!("Error presign-getting object: status {}", resp.status());
+                }
+                Err(e) => {
+                    eprintln!("Error presign-getting object: {:?}", e);
                 }
-                println!("after put");
             }
-        });
-        tasks_future.push(put_task_future);
-    }
-
-    // spawn get threads
-    for _ in 0..get_thread_num {
-        let s3 = s3.clone();
-        let stats_vec = Arc::clone(&stats_vec);
-        let bucket = bucket.clone();
-        let root_prefix = root_prefix.clone();
-
-        let get_task_future = tokio::task::spawn(async move {
-            for _ in 0..get_per_thread {
-                let mut request = ListObjectsV2Request {
-                    bucket: bucket.clone(),
-                    prefix: Some(root_prefix.clone()),
-                    ..Default::default()
-                };
-                let mut objects = Vec::new();
-                loop {
-                    let result = s3.list_objects_v2(request.clone()).await.unwrap();
-                    objects.extend(result.contents.unwrap());
-                    if result.next_continuation_token.is_none() {
-                        break;
-                    }
-                    request.continuation_token = result.next_continuation_token;
+            continue;
+        }
+
+        let start_time = Instant::now();
+        let get_req = GetObjectRequest {
+            bucket: shared.bucket.clone(),
+            key: key.clone(),
+            ..Default::default()
+        };
+        let (result, retries) =
+            retry_with_backoff(shared.max_retries, || shared.s3.get_object(get_req.clone())).await;
+        match result {
+            Ok(resp) => match resp.body {
+                Some(body) => {
+                    let mut body = body.into_async_read();
+                    let mut buf = Vec::new();
+                    body.read_to_end(&mut buf).await.unwrap();
+                    let end_time = Instant::now();
+                    check_integrity(&shared, &key, &buf);
+                    shared.stats_vec.lock().unwrap().push(Stats {
+                        workload: wl.name.clone(),
+                        start_time,
+                        end_time,
+                        request_type: RequestType::Get,
+                        file_size: buf.len(),
+                        retries,
+                        sign_time_ms: None,
+                    });
                 }
-                println!("list objects size: {}", objects.len());
-                if objects.len() == 0 {
-                    tokio::time::sleep(std::time::Duration::from_secs(1)).await;
-                    continue;
+                None => {
+                    eprintln!("No body in response");
                 }
-                let key = objects[thread_rng().gen_range(0..objects.len())]
-                    .key
-                    .clone()
-                    .unwrap();
-                let start_time = Instant::now();
-                let get_req = GetObjectRequest {
-                    bucket: bucket.clone(),
-                    key: key.clone(),
-                    ..Default::default()
-                };
-                println!("before get {}", key);
-                match s3.get_object(get_req).await {
-                    Ok(resp) => match resp.body {
-                        Some(body) => {
-                            let mut body = body.into_async_read();
-                            let mut buf = Vec::new();
-                            body.read_to_end(&mut buf).await.unwrap();
-                            let end_time = Instant::now();
-                            let stats = Stats {
-                                start_time,
-                                end_time,
-                                request_type: RequestType::Get,
-                                file_size: buf.len(),
-                            };
-                            stats_vec.lock().unwrap().push(stats);
-                        }
-                        None => {
-                            eprintln!("No body in response");
-                        }
-                    },
-                    Err(RusotoError::HttpDispatch(_)) => {}
-                    Err(e) => {
-                        eprintln!("Error getting object: {:?}", e);
-                    }
-                }
-                println!("after get");
+            },
+            Err(RusotoError::HttpDispatch(_)) => {}
+            Err(e) => {
+                eprintln!("Error getting object: {:?}", e);
             }
+        }
+    }
+}
+
+async fn run_list_workload(shared: Shared, wl: WorkloadConfig) {
+    for _ in 0..wl.ops_per_thread {
+        let _in_flight = InFlightGuard::start(&shared.in_flight);
+        let start_time = Instant::now();
+        let objects = list_all(&shared, &wl.key_prefix).await;
+        let end_time = Instant::now();
+        shared.stats_vec.lock().unwrap().push(Stats {
+            workload: wl.name.clone(),
+            start_time,
+            end_time,
+            request_type: RequestType::List,
+            file_size: 0,
+            retries: 0,
+            sign_time_ms: None,
         });
-        tasks_future.push(get_task_future);
+    }
+}
+
+// Checks `buf` (bytes read back from a GET) against the digest recorded at PUT time, when
+// `shared.verify` is set and the key is one this run actually wrote.
+fn check_integrity(shared: &Shared, key: &str, buf: &[u8]) {
+    if !shared.verify {
+        return;
+    }
+    if let Some((expected_digest, expected_len)) = shared.digests.lock().unwrap().get(key).copied()
+    {
+        let mut verify_stats = shared.verify_stats.lock().unwrap();
+        if buf.len() != expected_len {
+            verify_stats.length_mismatch_count += 1;
+        }
+        if md5::compute(buf) != expected_digest {
+            verify_stats.corruption_count += 1;
+        }
+    }
+}
+
+fn request_type_label(request_type: &RequestType) -> &'static str {
+    match request_type {
+        RequestType::Put => "put",
+        RequestType::PutPart => "put_part",
+        RequestType::Get => "get",
+        RequestType::List => "list",
+        RequestType::PresignPut => "presign_put",
+        RequestType::PresignGet => "presign_get",
+    }
+}
+
+// Renders a snapshot of `shared.stats_vec` in Prometheus text exposition format, grouped by
+// operation type, so a scraper watching `/metrics` sees request counts, bytes, retries, a
+// latency histogram, and rolling throughput build up over the course of the run.
+fn render_prometheus_metrics(shared: &Shared) -> String {
+    let stat_vec = shared.stats_vec.lock().unwrap();
+    let mut by_type: HashMap<&'static str, Vec<&Stats>> = HashMap::new();
+    for stats in stat_vec.iter() {
+        by_type
+            .entry(request_type_label(&stats.request_type))
+            .or_default()
+            .push(stats);
+    }
+
+    let mut out = String::new();
+
+    out.push_str(
+        "# HELP s3_benchmark_requests_total Total requests completed, by operation type.\n",
+    );
+    out.push_str("# TYPE s3_benchmark_requests_total counter\n");
+    for (op, entries) in &by_type {
+        out.push_str(&format!(
+            "s3_benchmark_requests_total{{op=\"{}\"}} {}\n",
+            op,
+            entries.len()
+        ));
+    }
+
+    out.push_str("# HELP s3_benchmark_bytes_total Total bytes transferred, by operation type.\n");
+    out.push_str("# TYPE s3_benchmark_bytes_total counter\n");
+    for (op, entries) in &by_type {
+        let total_size: usize = entries.iter().map(|s| s.file_size).sum();
+        out.push_str(&format!(
+            "s3_benchmark_bytes_total{{op=\"{}\"}} {}\n",
+            op, total_size
+        ));
+    }
+
+    out.push_str("# HELP s3_benchmark_retries_total Total retries issued, by operation type.\n");
+    out.push_str("# TYPE s3_benchmark_retries_total counter\n");
+    for (op, entries) in &by_type {
+        let total_retries: usize = entries.iter().map(|s| s.retries).sum();
+        out.push_str(&format!(
+            "s3_benchmark_retries_total{{op=\"{}\"}} {}\n",
+            op, total_retries
+        ));
+    }
+
+    out.push_str(
+        "# HELP s3_benchmark_latency_ms Request latency in milliseconds, by operation type.\n",
+    );
+    out.push_str("# TYPE s3_benchmark_latency_ms histogram\n");
+    for (op, entries) in &by_type {
+        let durations: Vec<f64> = entries
+            .iter()
+            .map(|s| s.end_time.duration_since(s.start_time).as_millis() as f64)
+            .collect();
+        for bucket in LATENCY_BUCKETS_MS {
+            let count = durations.iter().filter(|d| **d <= *bucket).count();
+            out.push_str(&format!(
+                "s3_benchmark_latency_ms_bucket{{op=\"{}\",le=\"{}\"}} {}\n",
+                op, bucket, count
+            ));
+        }
+        out.push_str(&format!(
+            "s3_benchmark_latency_ms_bucket{{op=\"{}\",le=\"+Inf\"}} {}\n",
+            op,
+            durations.len()
+        ));
+        out.push_str(&format!(
+            "s3_benchmark_latency_ms_sum{{op=\"{}\"}} {}\n",
+            op,
+            durations.iter().sum::<f64>()
+        ));
+        out.push_str(&format!(
+            "s3_benchmark_latency_ms_count{{op=\"{}\"}} {}\n",
+            op,
+            durations.len()
+        ));
+    }
+
+    out.push_str(
+        "# HELP s3_benchmark_throughput_mb_s Rolling throughput over the last 5s, by operation type.\n",
+    );
+    out.push_str("# TYPE s3_benchmark_throughput_mb_s gauge\n");
+    let now = Instant::now();
+    for (op, entries) in &by_type {
+        let window_bytes: usize = entries
+            .iter()
+            .filter(|s| now.duration_since(s.end_time) <= ROLLING_THROUGHPUT_WINDOW)
+            .map(|s| s.file_size)
+            .sum();
+        let throughput_mb_s =
+            (window_bytes as f64 / 1024.0 / 1024.0) / ROLLING_THROUGHPUT_WINDOW.as_secs_f64();
+        out.push_str(&format!(
+            "s3_benchmark_throughput_mb_s{{op=\"{}\"}} {:.4}\n",
+            op, throughput_mb_s
+        ));
+    }
+
+    out.push_str("# HELP s3_benchmark_in_flight_requests Requests currently in flight.\n");
+    out.push_str("# TYPE s3_benchmark_in_flight_requests gauge\n");
+    out.push_str(&format!(
+        "s3_benchmark_in_flight_requests {}\n",
+        shared.in_flight.load(Ordering::Relaxed)
+    ));
+
+    out
+}
+
+async fn handle_metrics_request(
+    req: Request<Body>,
+    shared: Shared,
+) -> Result<Response<Body>, Infallible> {
+    if req.uri().path() != "/metrics" {
+        return Ok(Response::builder()
+            .status(404)
+            .body(Body::from("not found"))
+            .unwrap());
+    }
+    Ok(Response::new(Body::from(render_prometheus_metrics(
+        &shared,
+    ))))
+}
+
+// Serves a live Prometheus snapshot of the run's stats on `addr` for as long as the process
+// keeps running, so operators can scrape it into Grafana instead of waiting for the final
+// summary.
+async fn serve_metrics(shared: Shared, addr: SocketAddr) {
+    let make_svc = make_service_fn(move |_conn| {
+        let shared = shared.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                handle_metrics_request(req, shared.clone())
+            }))
+        }
+    });
+    if let Err(e) = Server::bind(&addr).serve(make_svc).await {
+        eprintln!("metrics server error: {:?}", e);
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let num_args = env::args().len();
+    if num_args != 2 {
+        println!("Usage: {} config.toml", env::args().next().unwrap());
+        std::process::exit(1);
+    }
+    let config_path = env::args().nth(1).unwrap();
+    let config_text = std::fs::read_to_string(&config_path)
+        .unwrap_or_else(|e| panic!("failed to read {}: {}", config_path, e));
+    let config: Config = toml::from_str(&config_text)
+        .unwrap_or_else(|e| panic!("failed to parse {}: {}", config_path, e));
+
+    let region = Region::Custom {
+        name: "us-east-2".to_owned(),
+        endpoint: config.endpoint.clone(),
+    };
+    let credentials = DefaultCredentialsProvider::new()
+        .unwrap()
+        .credentials()
+        .await
+        .unwrap();
+    let s3 = S3Client::new(region.clone());
+    let http_client = reqwest::Client::new();
+
+    let shared = Shared {
+        s3,
+        http_client,
+        region,
+        credentials,
+        bucket: config.bucket.clone(),
+        max_retries: config.max_retries,
+        verify: config.verify,
+        presign: config.presign,
+        stats_vec: Arc::new(Mutex::new(Vec::new())),
+        digests: Arc::new(Mutex::new(HashMap::new())),
+        verify_stats: Arc::new(Mutex::new(VerifyStats::default())),
+        in_flight: Arc::new(AtomicI64::new(0)),
+        next_key_id: Arc::new(AtomicUsize::new(0)),
+    };
+
+    if let Some(metrics_port) = config.metrics_port {
+        let metrics_shared = shared.clone();
+        let addr = SocketAddr::from(([0, 0, 0, 0], metrics_port));
+        tokio::task::spawn(serve_metrics(metrics_shared, addr));
+        println!("serving metrics on http://{}/metrics", addr);
+    }
+
+    let mut tasks_future = Vec::new();
+    for wl in &config.workloads {
+        for _ in 0..wl.thread_num {
+            let shared = shared.clone();
+            let wl = wl.clone();
+            let task_future = match wl.op {
+                Op::Put => tokio::task::spawn(run_put_workload(shared, wl)),
+                Op::Multipart => tokio::task::spawn(run_multipart_workload(shared, wl)),
+                Op::Get => tokio::task::spawn(run_get_workload(shared, wl)),
+                Op::List => tokio::task::spawn(run_list_workload(shared, wl)),
+            };
+            tasks_future.push(task_future);
+        }
     }
 
     println!("waiting for futures");
     let _results = block_on(futures::future::join_all(tasks_future));
     println!("Done!");
 
-    let mut put_count = 0;
-    let mut get_count = 0;
-    let mut put_time = 0;
-    let mut get_time = 0;
-    let mut put_file_size = 0;
-    let mut get_file_size = 0;
-    let stat_vec = stats_vec.lock().unwrap();
-    for i in stat_vec.iter() {
-        match i.request_type {
-            RequestType::Put => {
-                put_count += 1;
-                put_time += i.end_time.duration_since(i.start_time).as_millis() as u128;
-                put_file_size += i.file_size;
-            }
-            RequestType::Get => {
-                get_count += 1;
-                get_time += i.end_time.duration_since(i.start_time).as_millis() as u128;
-                get_file_size += i.file_size;
-            }
-        }
+    let stat_vec = shared.stats_vec.lock().unwrap();
+    for wl in &config.workloads {
+        println!("workload \"{}\" ({:?}):", wl.name, wl.op);
+        let entries: Vec<&Stats> = stat_vec.iter().filter(|s| s.workload == wl.name).collect();
+
+        let put_stats: Vec<&Stats> = entries
+            .iter()
+            .filter(|s| matches!(s.request_type, RequestType::Put))
+            .copied()
+            .collect();
+        let put_part_stats: Vec<&Stats> = entries
+            .iter()
+            .filter(|s| matches!(s.request_type, RequestType::PutPart))
+            .copied()
+            .collect();
+        let get_stats: Vec<&Stats> = entries
+            .iter()
+            .filter(|s| matches!(s.request_type, RequestType::Get))
+            .copied()
+            .collect();
+        let list_stats: Vec<&Stats> = entries
+            .iter()
+            .filter(|s| matches!(s.request_type, RequestType::List))
+            .copied()
+            .collect();
+        let presign_put_stats: Vec<&Stats> = entries
+            .iter()
+            .filter(|s| matches!(s.request_type, RequestType::PresignPut))
+            .copied()
+            .collect();
+        let presign_get_stats: Vec<&Stats> = entries
+            .iter()
+            .filter(|s| matches!(s.request_type, RequestType::PresignGet))
+            .copied()
+            .collect();
+
+        print_latency_summary("PUT", &put_stats);
+        print_latency_summary("PUT PART", &put_part_stats);
+        print_latency_summary("GET", &get_stats);
+        print_latency_summary("LIST", &list_stats);
+        print_latency_summary("PRESIGN PUT", &presign_put_stats);
+        print_presign_sign_time(&presign_put_stats);
+        print_latency_summary("PRESIGN GET", &presign_get_stats);
+        print_presign_sign_time(&presign_get_stats);
     }
-    let put_avg_time = put_time / put_count as u128;
-    let get_avg_time = get_time / get_count as u128;
 
+    let total_retries: usize = stat_vec.iter().map(|s| s.retries).sum();
+    let total_requests = stat_vec.len();
+    let retry_rate = if total_requests > 0 {
+        total_retries as f64 / total_requests as f64
+    } else {
+        0.0
+    };
     println!(
-        "PUT stats: count={}, total_time={}ms, avg_time={}ms, total_size={} MB",
-        put_count,
-        put_time,
-        put_avg_time,
-        put_file_size / 1024 / 1024
-    );
-    println!(
-        "GET stats: count={}, total_time={}ms, avg_time={}ms, total_size={} MB",
-        get_count,
-        get_time,
-        get_avg_time,
-        get_file_size / 1024 / 1024
+        "RETRY stats: total_retries={}, retry_rate={:.4} retries/request",
+        total_retries, retry_rate
     );
+
+    if config.verify {
+        let verify_stats = shared.verify_stats.lock().unwrap();
+        println!(
+            "VERIFY stats: corruption_count={}, length_mismatch_count={}",
+            verify_stats.corruption_count, verify_stats.length_mismatch_count
+        );
+    }
 }